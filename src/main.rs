@@ -2,11 +2,26 @@ mod node;
 mod tests;
 
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
 use std::collections::HashMap;
 use tokio::time::Duration;
-use node::{Node, state::State};
+use node::{Identity, Node, PeerInfo, WriteAheadLog, state::State};
 
+/// Polls `node`'s state until it matches `expected` or `timeout` elapses, so
+/// callers don't have to sleep a fixed, guessed-at duration while waiting for
+/// a peer to finish applying a committed state.
+async fn wait_for_state(node: &Node, expected: &State, timeout: Duration) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if *node.state.lock().await == *expected {
+            return;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            panic!("timed out waiting for node {} state to become {:?}", node.id, expected);
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
 
 /// Main function for running the node communication simulation.
 ///
@@ -14,29 +29,61 @@ use node::{Node, state::State};
 /// the process of broadcasting a proposal and handling acknowledgments between nodes.
 #[tokio::main]
 async fn main() {
-    // Initialize shared state and proposal acknowledgments
-    let state = Arc::new(Mutex::new(State::Init));
+    // Both nodes share one acknowledgment tracker; unlike their state, it is
+    // not recovered from the write-ahead log.
     let proposal_acknowledgments = Arc::new(Mutex::new(HashMap::new()));
 
+    // Generate each node's long-lived identity before wiring up peer lists,
+    // since a peer is trusted by its public key, not just its address.
+    let node1_identity = Identity::generate();
+    let node2_identity = Identity::generate();
+
+    // Replay each node's write-ahead log to recover the state it last
+    // committed before it stopped, instead of always starting at `Init`.
+    let node1_log = WriteAheadLog::open("node1.wal.log").await.expect("failed to open node1's write-ahead log");
+    let node1_recovered = WriteAheadLog::replay("node1.wal.log").await.expect("failed to replay node1's write-ahead log");
+    let node2_log = WriteAheadLog::open("node2.wal.log").await.expect("failed to open node2's write-ahead log");
+    let node2_recovered = WriteAheadLog::replay("node2.wal.log").await.expect("failed to replay node2's write-ahead log");
+
     // Create channels for message passing
     let (tx1, rx1) = mpsc::channel(32);
     let node1 = Arc::new(Node {
         id: 1,
-        state: state.clone(),
-        peers: HashMap::from([(2, "127.0.0.1:8081".to_string())]), // Peer node2's address
+        identity: node1_identity,
+        state: Arc::new(Mutex::new(node1_recovered.committed_state)),
+        peers: HashMap::from([(2, PeerInfo {
+            address: "127.0.0.1:8081".to_string(), // Peer node2's address
+            public_key: node2_identity.public_key(),
+        })]),
         address: "127.0.0.1:8080".to_string(), // Node1's address
         tx: tx1,
         proposal_acknowledgments: proposal_acknowledgments.clone(),
+        connections: Arc::new(Mutex::new(HashMap::new())),
+        proposal_waiters: Arc::new(Mutex::new(HashMap::new())),
+        peer_capabilities: Arc::new(Mutex::new(HashMap::new())),
+        log: node1_log,
+        shutdown: watch::channel(false).0,
+        listening: watch::channel(false).0,
     });
 
     let (tx2, rx2) = mpsc::channel(32);
     let node2 = Arc::new(Node {
         id: 2,
-        state: state.clone(),
-        peers: HashMap::from([(1, "127.0.0.1:8080".to_string())]), // Peer node1's address
+        identity: node2_identity,
+        state: Arc::new(Mutex::new(node2_recovered.committed_state)),
+        peers: HashMap::from([(1, PeerInfo {
+            address: "127.0.0.1:8080".to_string(), // Peer node1's address
+            public_key: node1.identity.public_key(),
+        })]),
         address: "127.0.0.1:8081".to_string(), // Node2's address
         tx: tx2,
         proposal_acknowledgments,
+        connections: Arc::new(Mutex::new(HashMap::new())),
+        proposal_waiters: Arc::new(Mutex::new(HashMap::new())),
+        peer_capabilities: Arc::new(Mutex::new(HashMap::new())),
+        log: node2_log,
+        shutdown: watch::channel(false).0,
+        listening: watch::channel(false).0,
     });
 
     // Spawn tasks for handling incoming messages for each node
@@ -61,8 +108,19 @@ async fn main() {
         node2_clone_for_listen.listen().await.expect("Node 2 failed to listen");
     });
 
-    // Ensure the servers have time to start up and bind to their addresses
-    tokio::time::sleep(Duration::from_secs(1)).await;
+    // Wait for both listeners to actually be bound, instead of sleeping a
+    // fixed duration and hoping it was long enough.
+    node1.wait_until_listening().await;
+    node2.wait_until_listening().await;
+
+    // Resume any proposals that were in flight when a node last stopped,
+    // instead of leaving them unresolved now that peers are reachable again.
+    for (proposal_id, proposed_state) in node1_recovered.in_flight_proposals {
+        node1.resume_proposal(proposal_id, proposed_state).await;
+    }
+    for (proposal_id, proposed_state) in node2_recovered.in_flight_proposals {
+        node2.resume_proposal(proposal_id, proposed_state).await;
+    }
 
     // Use node1 to broadcast a proposal
     let proposal_id = node1.broadcast_proposal(State::Running).await;
@@ -70,8 +128,8 @@ async fn main() {
     // Wait for acknowledgments for the proposal
     node1.wait_for_acknowledgments(proposal_id).await;
 
-    // Allow additional time for Node 2 to process the acknowledgment and update its state
-    tokio::time::sleep(Duration::from_secs(2)).await;
+    // Wait for Node 2 to process the proposal and update its state.
+    wait_for_state(&node2, &State::Running, Duration::from_secs(2)).await;
 
     // Check if Node 2 has updated its state to the proposed state
     let state = node2.state.lock().await;
@@ -79,4 +137,9 @@ async fn main() {
 
     // Print success message if the communication was successful
     println!("Communication completed successfully!");
+
+    // Shut down both nodes' listen and message-handling loops cleanly rather
+    // than just exiting the process out from under them.
+    node1.shutdown();
+    node2.shutdown();
 }
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// The protocol version this build of the crate speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A bitflag set advertising which optional protocol features a node understands.
+///
+/// New features are given their own bit instead of growing the `MessageType`
+/// enum in a breaking way, so an older node that doesn't recognize a bit
+/// simply never sets or acts on it, rather than failing to parse the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    /// A node that supports no optional capabilities beyond the base protocol.
+    pub const NONE: Capabilities = Capabilities(0);
+
+    /// Support for a (future) v2 commit protocol.
+    pub const COMMIT_V2: Capabilities = Capabilities(1 << 0);
+
+    /// Returns the capability set this build of the crate implements.
+    pub fn supported() -> Capabilities {
+        Capabilities::NONE
+    }
+
+    /// Returns a copy of this set with `COMMIT_V2` added.
+    pub fn with_commit_v2(self) -> Capabilities {
+        Capabilities(self.0 | Self::COMMIT_V2.0)
+    }
+
+    /// Returns whether this set has every flag that is set in `other`.
+    pub fn includes(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the capabilities both this set and `other` have in common.
+    pub fn intersection(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+}
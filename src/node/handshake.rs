@@ -0,0 +1,146 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io;
+use tokio::net::TcpStream;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey};
+
+use super::identity::{self, Identity, PublicKeyBytes};
+use super::proto::{read_frame, write_frame};
+
+/// The plaintext message exchanged once, in each direction, when a connection
+/// is first established, before any application `Message` is sent.
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    node_id: u64,
+    public_key: PublicKeyBytes,
+    ephemeral_public_key: [u8; 32],
+    signature: Vec<u8>,
+}
+
+/// Which side of the handshake this node is playing.
+#[derive(Clone, Copy)]
+pub enum Role {
+    /// The side that dialed the connection.
+    Initiator,
+    /// The side that accepted the connection.
+    Responder,
+}
+
+/// An authenticated, encrypted session established with a single peer.
+///
+/// Every `Message` sent or received after the handshake is encrypted and
+/// authenticated under this session's keys, so a node that has not completed
+/// a valid handshake cannot inject or read traffic.
+pub struct Session {
+    send_cipher: ChaCha20Poly1305,
+    recv_cipher: ChaCha20Poly1305,
+    send_nonce: AtomicU64,
+    recv_nonce: AtomicU64,
+}
+
+impl Session {
+    /// Encrypts `plaintext` under this session's send key, ready to be framed onto the wire.
+    pub fn encrypt(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = nonce_from_counter(self.send_nonce.fetch_add(1, Ordering::SeqCst));
+        self.send_cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to encrypt message"))
+    }
+
+    /// Decrypts `ciphertext` read off the wire under this session's receive key.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = nonce_from_counter(self.recv_nonce.fetch_add(1, Ordering::SeqCst));
+        self.recv_cipher
+            .decrypt(&nonce, ciphertext)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed to authenticate message"))
+    }
+}
+
+/// Builds a 96-bit nonce from a monotonically increasing per-direction counter.
+fn nonce_from_counter(counter: u64) -> Nonce {
+    let mut bytes = [0u8; 12];
+    bytes[4..].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&bytes)
+}
+
+/// Derives the two directional session keys from a completed Diffie-Hellman exchange.
+fn derive_directional_keys(shared_secret: &[u8; 32]) -> (Key, Key) {
+    let initiator_to_responder = Sha256::new()
+        .chain_update(shared_secret)
+        .chain_update(b"state_machine-i2r")
+        .finalize();
+    let responder_to_initiator = Sha256::new()
+        .chain_update(shared_secret)
+        .chain_update(b"state_machine-r2i")
+        .finalize();
+
+    (
+        *Key::from_slice(&initiator_to_responder),
+        *Key::from_slice(&responder_to_initiator),
+    )
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed the connection during handshake")
+}
+
+/// Performs the mutual handshake over a freshly connected or accepted `stream`.
+///
+/// Returns the peer's claimed node id, its public key, and the `Session`
+/// established for subsequent encrypted traffic. The caller is responsible
+/// for checking the returned id/public key against its known peer list;
+/// a session is only trustworthy once that check has passed.
+pub async fn perform(stream: &mut TcpStream, identity: &Identity, own_id: u64, role: Role) -> io::Result<(u64, PublicKeyBytes, Session)> {
+    let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = X25519PublicKey::from(&ephemeral_secret);
+
+    let hello = Hello {
+        node_id: own_id,
+        public_key: identity.public_key(),
+        ephemeral_public_key: ephemeral_public.to_bytes(),
+        signature: identity.sign(&ephemeral_public.to_bytes()).to_bytes().to_vec(),
+    };
+    let hello_bytes = serde_json::to_vec(&hello)?;
+
+    let peer_hello: Hello = match role {
+        Role::Initiator => {
+            write_frame(stream, &hello_bytes).await?;
+            let payload = read_frame(stream).await?.ok_or_else(unexpected_eof)?;
+            serde_json::from_slice(&payload)?
+        }
+        Role::Responder => {
+            let payload = read_frame(stream).await?.ok_or_else(unexpected_eof)?;
+            let peer_hello = serde_json::from_slice(&payload)?;
+            write_frame(stream, &hello_bytes).await?;
+            peer_hello
+        }
+    };
+
+    let signature = ed25519_dalek::Signature::from_slice(&peer_hello.signature)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed handshake signature"))?;
+    if !identity::verify(&peer_hello.public_key, &peer_hello.ephemeral_public_key, &signature) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "handshake signature did not verify"));
+    }
+
+    let peer_ephemeral = X25519PublicKey::from(peer_hello.ephemeral_public_key);
+    let shared_secret = ephemeral_secret.diffie_hellman(&peer_ephemeral);
+    let (initiator_to_responder, responder_to_initiator) = derive_directional_keys(shared_secret.as_bytes());
+
+    let (send_key, recv_key) = match role {
+        Role::Initiator => (initiator_to_responder, responder_to_initiator),
+        Role::Responder => (responder_to_initiator, initiator_to_responder),
+    };
+
+    let session = Session {
+        send_cipher: ChaCha20Poly1305::new(&send_key),
+        recv_cipher: ChaCha20Poly1305::new(&recv_key),
+        send_nonce: AtomicU64::new(0),
+        recv_nonce: AtomicU64::new(0),
+    };
+
+    Ok((peer_hello.node_id, peer_hello.public_key, session))
+}
@@ -0,0 +1,37 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+
+/// Raw ed25519 public key bytes, used as a peer's stable identity.
+pub type PublicKeyBytes = [u8; 32];
+
+/// A node's long-lived ed25519 keypair, used to sign and verify handshakes.
+pub struct Identity {
+    signing_key: SigningKey,
+}
+
+impl Identity {
+    /// Generates a fresh random identity.
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Returns this identity's public key.
+    pub fn public_key(&self) -> PublicKeyBytes {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    /// Signs `message` with this identity's private key.
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.signing_key.sign(message)
+    }
+}
+
+/// Verifies that `signature` over `message` was produced by `public_key`.
+pub fn verify(public_key: &PublicKeyBytes, message: &[u8], signature: &Signature) -> bool {
+    match VerifyingKey::from_bytes(public_key) {
+        Ok(key) => key.verify(message, signature).is_ok(),
+        Err(_) => false,
+    }
+}
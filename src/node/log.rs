@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use super::state::State;
+
+/// A single durable fact recorded before the corresponding in-memory mutation happens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LogRecord {
+    /// A proposal this node sent (before broadcasting it) or received (before
+    /// it applies the proposed state).
+    Proposal { proposal_id: String, proposed_state: State },
+
+    /// An acknowledgment this node received for one of its own proposals.
+    Acknowledgment { proposal_id: String, acknowledging_peer: u64 },
+
+    /// A proposal this node has committed (before notifying peers), or whose
+    /// commit it received from a peer.
+    Commit { proposal_id: String, committed_state: State },
+}
+
+/// The result of replaying a node's write-ahead log on startup.
+pub struct RecoveredState {
+    /// The state of the last record committed before the node last stopped.
+    pub committed_state: State,
+
+    /// Proposals that were recorded as proposed but never reached a commit
+    /// record, keyed by `proposal_id`, with the state each one proposed so
+    /// the node can resume them instead of letting them go unresolved.
+    pub in_flight_proposals: HashMap<String, State>,
+}
+
+impl Default for RecoveredState {
+    fn default() -> Self {
+        Self {
+            committed_state: State::Init,
+            in_flight_proposals: HashMap::new(),
+        }
+    }
+}
+
+/// An append-only, fsync'd log of every proposal, acknowledgment, and commit a
+/// node processes, so that a restart can deterministically reconstruct the
+/// last agreed state instead of losing all consensus progress.
+pub struct WriteAheadLog {
+    file: Mutex<File>,
+}
+
+impl WriteAheadLog {
+    /// Opens (creating if necessary) the log file at `path` for appending.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path).await?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    /// Appends `record` to the log and fsyncs before returning, so the record
+    /// is durable on disk before the caller mutates any in-memory state based on it.
+    pub async fn append(&self, record: &LogRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        file.sync_data().await
+    }
+
+    /// Replays every record in the log at `path`, reconstructing the last
+    /// committed state and the set of proposals that were seen but never committed.
+    ///
+    /// Returns the default, freshly-initialized state if `path` does not exist yet.
+    pub async fn replay(path: impl AsRef<Path>) -> io::Result<RecoveredState> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(RecoveredState::default());
+        }
+
+        let file = File::open(path).await?;
+        let mut lines = BufReader::new(file).lines();
+
+        let mut committed_state = State::Init;
+        let mut in_flight: HashMap<String, State> = HashMap::new();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(&line)? {
+                LogRecord::Proposal { proposal_id, proposed_state } => {
+                    in_flight.insert(proposal_id, proposed_state);
+                }
+                LogRecord::Acknowledgment { .. } => {}
+                LogRecord::Commit { proposal_id, committed_state: state } => {
+                    in_flight.remove(&proposal_id);
+                    committed_state = state;
+                }
+            }
+        }
+
+        Ok(RecoveredState {
+            committed_state,
+            in_flight_proposals: in_flight,
+        })
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use super::capabilities::Capabilities;
 use super::state::State;
 
 /// Represents the type of message being sent between nodes.
@@ -12,6 +13,13 @@ pub enum MessageType {
 
     /// A commit message indicating that a proposal has been committed.
     Commit,
+
+    /// A handshake message negotiating protocol version and capabilities,
+    /// exchanged once right after a connection is established. A message
+    /// whose `MessageType` this build does not recognize fails to
+    /// deserialize in `listen` and is dropped there, without closing the
+    /// connection, so newer variants do not break older nodes.
+    Handshake,
 }
 
 /// Represents a message exchanged between nodes in the system.
@@ -28,4 +36,12 @@ pub struct Message {
 
     /// The unique identifier of the proposal.
     pub proposal_id: String,
+
+    /// The sender's supported protocol version. Only populated on `MessageType::Handshake`.
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+
+    /// The sender's supported capability flags. Only populated on `MessageType::Handshake`.
+    #[serde(default)]
+    pub capabilities: Option<Capabilities>,
 }
@@ -0,0 +1,14 @@
+pub mod capabilities;
+pub mod handshake;
+pub mod identity;
+pub mod log;
+pub mod message;
+pub mod proto;
+pub mod state;
+
+mod node;
+
+pub use capabilities::Capabilities;
+pub use identity::Identity;
+pub use log::WriteAheadLog;
+pub use node::{Node, PeerInfo};
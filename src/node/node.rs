@@ -1,198 +1,632 @@
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, Mutex};
-use tokio::time::Duration;
-use uuid::Uuid;
-
-use super::message::{Message, MessageType};
-use super::state::State;
-
-/// Represents a node in the distributed system.
-pub struct Node {
-    /// Unique identifier for the node.
-    pub id: u64,
-
-    /// The current state of the node, wrapped in an `Arc` and `Mutex` for concurrency.
-    pub state: Arc<Mutex<State>>,
-
-    /// Map of peer node IDs to their network addresses.
-    pub peers: HashMap<u64, String>,
-
-    /// Address the node is listening on for incoming connections.
-    pub address: String,
-
-    /// Channel sender used to send messages to the node's message handler.
-    pub tx: mpsc::Sender<Message>,
-
-    /// Tracks acknowledgments for each proposal, with `proposal_id` as the key and a set of node IDs as the value.
-    pub proposal_acknowledgments: Arc<Mutex<HashMap<String, HashSet<u64>>>>,
-}
-
-impl Node {
-    /// Sends a message to a specified address.
-    ///
-    /// # Arguments
-    ///
-    /// * `message` - The message to be sent.
-    /// * `address` - The destination address.
-    ///
-    /// # Returns
-    ///
-    /// Returns an `io::Result<()>` indicating success or failure.
-    pub async fn send_message(&self, message: &Message, address: &str) -> io::Result<()> {
-        let mut stream = TcpStream::connect(address).await?;
-        let serialized_message = serde_json::to_vec(message)?;
-        stream.write_all(&serialized_message).await?;
-        stream.flush().await
-    }
-
-    /// Broadcasts a proposal to all peer nodes.
-    ///
-    /// # Arguments
-    ///
-    /// * `new_state` - The proposed state to be broadcasted.
-    ///
-    /// # Returns
-    ///
-    /// Returns the `proposal_id` of the broadcasted proposal.
-    pub async fn broadcast_proposal(&self, new_state: State) -> String {
-        let proposal_id = Uuid::new_v4().to_string();
-        let proposal_message = Message {
-            sender_id: self.id,
-            message_type: MessageType::Proposal,
-            proposed_state: new_state,
-            proposal_id: proposal_id.clone(),
-        };
-
-        for peer_address in self.peers.values() {
-            if let Err(e) = self.send_message(&proposal_message, peer_address).await {
-                eprintln!("Failed to send proposal: {:?}", e);
-            }
-        }
-
-        println!("Node {} broadcasted the proposal: {}", self.id, proposal_id);
-
-        proposal_id
-    }
-
-    /// Waits for acknowledgments of a proposal and commits it if a majority is reached.
-    ///
-    /// # Arguments
-    ///
-    /// * `proposal_id` - The unique identifier of the proposal to wait for.
-    pub async fn wait_for_acknowledgments(&self, proposal_id: String) {
-        let majority = (self.peers.len() / 2) + 1; // Simple majority
-
-        loop {
-            let ack_count = {
-                let acks = self.proposal_acknowledgments.lock().await;
-                acks.get(&proposal_id)
-                    .map(|acks| acks.len())
-                    .unwrap_or(0)
-            };
-
-            if ack_count >= majority {
-                // Create and send commit message
-                let commit_message = Message {
-                    sender_id: self.id,
-                    message_type: MessageType::Commit,
-                    proposed_state: State::Running, // This should match the state proposed earlier
-                    proposal_id: proposal_id.clone(),
-                };
-
-                for address in self.peers.values() {
-                    self.send_message(&commit_message, address).await.unwrap();
-                }
-
-                println!("Node {} committed the proposal: {}", self.id, proposal_id);
-                break;
-            }
-
-            // Sleep briefly before checking again
-            tokio::time::sleep(Duration::from_millis(100)).await;
-        }
-    }
-
-    /// Handles incoming messages from a receiver.
-    ///
-    /// # Arguments
-    ///
-    /// * `receiver` - The channel receiver used to receive messages.
-    pub async fn handle_incoming_messages(&self, mut receiver: mpsc::Receiver<Message>) {
-        while let Some(message) = receiver.recv().await {
-            match message.message_type {
-                MessageType::Proposal => {
-                    // Handle proposal
-                    println!("Node {} received proposal: {:?}", self.id, message);
-                    let ack_message = Message {
-                        sender_id: self.id,
-                        message_type: MessageType::Acknowledgment,
-                        proposed_state: message.proposed_state.clone(),
-                        proposal_id: message.proposal_id.clone(),
-                    };
-                    if let Err(e) = self.send_message(&ack_message, &self.peers[&message.sender_id]).await {
-                        eprintln!("Failed to send acknowledgment: {:?}", e);
-                    }
-
-                    // Update the state to the proposed state
-                    let mut state = self.state.lock().await;
-                    *state = message.proposed_state;
-                    println!("Node {} updated state to {:?}", self.id, *state);
-                }
-                MessageType::Acknowledgment => {
-                    // Handle acknowledgment
-                    println!("Node {} received acknowledgment: {:?}", self.id, message);
-                    let mut acks = self.proposal_acknowledgments.lock().await;
-                    if let Some(ack_set) = acks.get_mut(&message.proposal_id) {
-                        ack_set.insert(message.sender_id);
-                    } else {
-                        let mut new_ack_set = HashSet::new();
-                        new_ack_set.insert(message.sender_id);
-                        acks.insert(message.proposal_id.clone(), new_ack_set);
-                    }
-                }
-                _ => {}
-            }
-        }
-    }
-
-    /// Listens for incoming connections and processes them.
-    ///
-    /// # Returns
-    ///
-    /// Returns an `io::Result<()>` indicating success or failure.
-    pub async fn listen(&self) -> io::Result<()> {
-        let listener = TcpListener::bind(&self.address).await?;
-        println!("Node {} listening on {}", self.id, self.address);
-
-        loop {
-            let (mut socket, _) = listener.accept().await?;
-
-            let tx = self.tx.clone();
-            tokio::spawn(async move {
-                let mut buf = [0u8; 1024];
-                loop {
-                    match socket.read(&mut buf).await {
-                        Ok(0) => {
-                            println!("Connection closed");
-                            break; // Connection was closed
-                        }
-                        Ok(n) => {
-                            if let Ok(message) = serde_json::from_slice::<Message>(&buf[..n]) {
-                                tx.send(message).await.expect("Failed to send message to channel");
-                            } else {
-                                println!("Failed to deserialize message");
-                            }
-                        }
-                        Err(e) => {
-                            println!("Failed to read from socket: {:?}", e);
-                            break;
-                        }
-                    }
-                }
-            });
-        }
-    }
-}
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::io;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::time::Duration;
+use uuid::Uuid;
+
+use super::capabilities::{Capabilities, PROTOCOL_VERSION};
+use super::handshake::{self, Role, Session};
+use super::identity::{Identity, PublicKeyBytes};
+use super::log::{LogRecord, WriteAheadLog};
+use super::message::{Message, MessageType};
+use super::proto::{read_frame, write_frame};
+use super::state::State;
+
+/// A peer's network address and the public key it must present during the handshake.
+#[derive(Clone)]
+pub struct PeerInfo {
+    /// The address to dial to reach this peer.
+    pub address: String,
+
+    /// The ed25519 public key this peer must prove ownership of during the handshake.
+    pub public_key: PublicKeyBytes,
+}
+
+/// A live, authenticated connection to a peer: the underlying socket plus the
+/// encrypted session negotiated with it.
+pub(crate) struct PeerConnection {
+    stream: Arc<Mutex<TcpStream>>,
+    session: Arc<Session>,
+}
+
+/// Represents a node in the distributed system.
+pub struct Node {
+    /// Unique identifier for the node.
+    pub id: u64,
+
+    /// This node's long-lived ed25519 identity, used to authenticate handshakes.
+    pub identity: Identity,
+
+    /// The current state of the node, wrapped in an `Arc` and `Mutex` for concurrency.
+    pub state: Arc<Mutex<State>>,
+
+    /// Map of peer node IDs to their network address and expected public key.
+    pub peers: HashMap<u64, PeerInfo>,
+
+    /// Address the node is listening on for incoming connections.
+    pub address: String,
+
+    /// Channel sender used to send messages to the node's message handler.
+    pub tx: mpsc::Sender<Message>,
+
+    /// Tracks acknowledgments for each proposal, with `proposal_id` as the key and a set of node IDs as the value.
+    pub proposal_acknowledgments: Arc<Mutex<HashMap<String, HashSet<u64>>>>,
+
+    /// Cache of live outbound connections, keyed by peer id, so repeated
+    /// sends to the same peer reuse one handshaked socket instead of dialing anew.
+    pub(crate) connections: Arc<Mutex<HashMap<u64, PeerConnection>>>,
+
+    /// One-shot wakers for proposals currently being awaited, keyed by
+    /// `proposal_id`. Fired as soon as the acknowledgment count for that
+    /// proposal crosses the majority threshold.
+    pub proposal_waiters: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+
+    /// The protocol version and capabilities negotiated with each peer during
+    /// its most recent handshake, keyed by peer id.
+    pub peer_capabilities: Arc<Mutex<HashMap<u64, (u32, Capabilities)>>>,
+
+    /// Durable, fsync'd record of every proposal, acknowledgment, and commit
+    /// this node processes, replayed on startup to recover consensus progress.
+    pub log: WriteAheadLog,
+
+    /// Sender side of this node's shutdown trigger. `listen` and
+    /// `handle_incoming_messages` each hold a receiver subscribed to it and
+    /// return once it fires, instead of looping forever.
+    pub(crate) shutdown: watch::Sender<bool>,
+
+    /// Sender side of this node's listening-readiness signal. `listen` sets
+    /// this to `true` right after it binds its socket, so callers can wait
+    /// deterministically for the listener to be ready instead of sleeping a
+    /// fixed, guessed-at duration.
+    pub(crate) listening: watch::Sender<bool>,
+}
+
+/// Maximum number of attempts `send_message` makes to reach a peer, counting
+/// the first one, before it gives up after a dial or write error.
+const MAX_SEND_RETRIES: u32 = 5;
+
+/// Base delay for the exponential backoff between redial attempts.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// How long `wait_for_acknowledgments` waits for a majority before giving up.
+const ACK_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl Node {
+    /// Returns the number of acknowledgments needed to commit a proposal.
+    fn majority(&self) -> usize {
+        (self.peers.len() / 2) + 1
+    }
+
+    /// Signals `listen` and `handle_incoming_messages` to stop at their next
+    /// opportunity: `listen` stops accepting new connections and returns,
+    /// and `handle_incoming_messages` drains no further messages.
+    pub fn shutdown(&self) {
+        let _ = self.shutdown.send(true);
+    }
+
+    /// Waits until `listen` has bound its socket and is ready to accept
+    /// connections, so a caller can dial this node as soon as it's reachable
+    /// instead of sleeping for a guessed-at startup duration.
+    pub async fn wait_until_listening(&self) {
+        let mut listening_rx = self.listening.subscribe();
+        if *listening_rx.borrow() {
+            return;
+        }
+        let _ = listening_rx.changed().await;
+    }
+
+    /// Returns the cached connection to `peer_id`, dialing and handshaking a fresh one if none is cached yet.
+    async fn connection_for(&self, peer_id: u64) -> io::Result<(Arc<Mutex<TcpStream>>, Arc<Session>)> {
+        {
+            let connections = self.connections.lock().await;
+            if let Some(conn) = connections.get(&peer_id) {
+                return Ok((conn.stream.clone(), conn.session.clone()));
+            }
+        }
+
+        let peer = self
+            .peers
+            .get(&peer_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unknown peer {}", peer_id)))?;
+
+        let mut stream = TcpStream::connect(&peer.address).await?;
+        let (handshaked_id, public_key, session) = handshake::perform(&mut stream, &self.identity, self.id, Role::Initiator).await?;
+
+        if handshaked_id != peer_id || public_key != peer.public_key {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("peer at {} did not present the expected identity", peer.address),
+            ));
+        }
+
+        let (version, capabilities) = self.negotiate_capabilities(&mut stream, &session, Role::Initiator).await?;
+        self.peer_capabilities.lock().await.insert(peer_id, (version, capabilities));
+
+        let stream = Arc::new(Mutex::new(stream));
+        let session = Arc::new(session);
+        self.connections.lock().await.insert(
+            peer_id,
+            PeerConnection {
+                stream: stream.clone(),
+                session: session.clone(),
+            },
+        );
+
+        Ok((stream, session))
+    }
+
+    /// Returns the protocol version and capability set negotiated with
+    /// `peer_id`, dialing and handshaking a connection first if one isn't
+    /// cached yet, so the minimum common version/capabilities are always
+    /// available before a message is built for that peer.
+    async fn negotiated_for(&self, peer_id: u64) -> io::Result<(u32, Capabilities)> {
+        self.connection_for(peer_id).await?;
+        self.peer_capabilities
+            .lock()
+            .await
+            .get(&peer_id)
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no negotiated capabilities for peer {}", peer_id)))
+    }
+
+    /// Exchanges a `MessageType::Handshake` message with the peer at the other
+    /// end of `stream`, right after the transport handshake establishes
+    /// `session`, and returns the version/capabilities negotiated with it.
+    async fn negotiate_capabilities(&self, stream: &mut TcpStream, session: &Session, role: Role) -> io::Result<(u32, Capabilities)> {
+        let own_message = Message {
+            sender_id: self.id,
+            message_type: MessageType::Handshake,
+            proposed_state: State::Init,
+            proposal_id: String::new(),
+            protocol_version: Some(PROTOCOL_VERSION),
+            capabilities: Some(Capabilities::supported()),
+        };
+        let own_bytes = serde_json::to_vec(&own_message)?;
+
+        let peer_message: Message = match role {
+            Role::Initiator => {
+                write_frame(stream, &session.encrypt(&own_bytes)?).await?;
+                let ciphertext = read_frame(stream)
+                    .await?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed during capability handshake"))?;
+                serde_json::from_slice(&session.decrypt(&ciphertext)?)?
+            }
+            Role::Responder => {
+                let ciphertext = read_frame(stream)
+                    .await?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed during capability handshake"))?;
+                let peer_message = serde_json::from_slice(&session.decrypt(&ciphertext)?)?;
+                write_frame(stream, &session.encrypt(&own_bytes)?).await?;
+                peer_message
+            }
+        };
+
+        let peer_version = peer_message.protocol_version.unwrap_or(0);
+        let peer_capabilities = peer_message.capabilities.unwrap_or(Capabilities::NONE);
+
+        Ok((PROTOCOL_VERSION.min(peer_version), Capabilities::supported().intersection(peer_capabilities)))
+    }
+
+    /// Sends a message to a peer over a cached, authenticated, encrypted connection.
+    ///
+    /// On a write failure the stale connection is evicted and a fresh one is
+    /// dialed (re-running the handshake) with exponential backoff between
+    /// attempts, so a node survives a peer restart instead of permanently
+    /// losing the message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to be sent.
+    /// * `peer_id` - The id of the destination peer, as registered in `peers`.
+    ///
+    /// # Returns
+    ///
+    /// Returns an `io::Result<()>` indicating success or failure.
+    pub async fn send_message(&self, message: &Message, peer_id: u64) -> io::Result<()> {
+        let serialized_message = serde_json::to_vec(message)?;
+        let mut backoff = RETRY_BASE_DELAY;
+
+        for attempt in 0..MAX_SEND_RETRIES {
+            // Dialing (and handshaking) a fresh connection is just as likely
+            // to fail as the write below when a peer is mid-restart, so it
+            // goes through the same retry/backoff path instead of using `?`
+            // and aborting on the first attempt.
+            let (stream, session) = match self.connection_for(peer_id).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    if attempt + 1 == MAX_SEND_RETRIES {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    continue;
+                }
+            };
+
+            let result = async {
+                let ciphertext = session.encrypt(&serialized_message)?;
+                let mut stream = stream.lock().await;
+                write_frame(&mut *stream, &ciphertext).await
+            }
+            .await;
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    self.connections.lock().await.remove(&peer_id);
+                    if attempt + 1 == MAX_SEND_RETRIES {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+
+        unreachable!("loop above always returns on its last iteration")
+    }
+
+    /// Broadcasts a proposal to all peer nodes.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_state` - The proposed state to be broadcasted.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `proposal_id` of the broadcasted proposal.
+    pub async fn broadcast_proposal(&self, new_state: State) -> String {
+        let proposal_id = Uuid::new_v4().to_string();
+        self.send_proposal(&proposal_id, &new_state).await;
+
+        println!("Node {} broadcasted the proposal: {}", self.id, proposal_id);
+
+        proposal_id
+    }
+
+    /// Re-sends a proposal that the write-ahead log recorded as proposed but
+    /// never reached a commit record before the node last stopped, so it
+    /// isn't silently forgotten on restart: peers re-acknowledge it (or
+    /// already have, if they also recovered it), and this waits on the same
+    /// `wait_for_acknowledgments` path a fresh proposal would, so the round
+    /// actually reaches commit instead of being re-broadcast and abandoned.
+    ///
+    /// # Arguments
+    ///
+    /// * `proposal_id` - The id the proposal was originally recorded under.
+    /// * `proposed_state` - The state the write-ahead log recorded it as proposing.
+    pub async fn resume_proposal(&self, proposal_id: String, proposed_state: State) {
+        println!(
+            "Node {} resuming in-flight proposal recovered from write-ahead log: {}",
+            self.id, proposal_id
+        );
+        self.send_proposal(&proposal_id, &proposed_state).await;
+        self.wait_for_acknowledgments(proposal_id).await;
+    }
+
+    /// Sends a `MessageType::Proposal` message for `proposal_id` to every
+    /// peer, built per peer with that peer's own negotiated minimum common
+    /// version/capabilities.
+    async fn send_proposal(&self, proposal_id: &str, new_state: &State) {
+        // Persist the proposal to our own write-ahead log before telling
+        // anyone about it, so a crash after broadcasting but before reaching
+        // quorum still leaves this node able to recover and resume it,
+        // rather than losing it entirely since only the receiving side
+        // otherwise logs a `LogRecord::Proposal`.
+        self.log
+            .append(&LogRecord::Proposal {
+                proposal_id: proposal_id.to_string(),
+                proposed_state: new_state.clone(),
+            })
+            .await
+            .expect("failed to persist proposal to write-ahead log");
+
+        for peer_id in self.peers.keys().copied().collect::<Vec<_>>() {
+            let (protocol_version, capabilities) = match self.negotiated_for(peer_id).await {
+                Ok((version, capabilities)) => (Some(version), Some(capabilities)),
+                Err(e) => {
+                    eprintln!("Failed to negotiate with peer {}: {:?}", peer_id, e);
+                    (None, None)
+                }
+            };
+            let proposal_message = Message {
+                sender_id: self.id,
+                message_type: MessageType::Proposal,
+                proposed_state: new_state.clone(),
+                proposal_id: proposal_id.to_string(),
+                protocol_version,
+                capabilities,
+            };
+
+            if let Err(e) = self.send_message(&proposal_message, peer_id).await {
+                eprintln!("Failed to send proposal: {:?}", e);
+            }
+        }
+    }
+
+    /// Waits for acknowledgments of a proposal and commits it if a majority is reached.
+    ///
+    /// Rather than polling, this registers a one-shot waker that
+    /// `handle_incoming_messages` fires the moment the acknowledgment count
+    /// crosses the majority threshold, so the commit fires immediately on
+    /// quorum instead of after the next poll tick.
+    ///
+    /// # Arguments
+    ///
+    /// * `proposal_id` - The unique identifier of the proposal to wait for.
+    pub async fn wait_for_acknowledgments(&self, proposal_id: String) {
+        // Check the current ack count and, if majority isn't met yet, register
+        // the waiter without releasing `proposal_acknowledgments` in between.
+        // `handle_incoming_messages` needs that same lock to record an
+        // incoming acknowledgment, so holding it across the check-and-insert
+        // serializes against a concurrently-arriving ack that would otherwise
+        // cross the majority threshold and fire before anyone is listening.
+        let waiter_rx = {
+            let acks = self.proposal_acknowledgments.lock().await;
+            let already_met = acks.get(&proposal_id).map(|acks| acks.len()).unwrap_or(0) >= self.majority();
+
+            if already_met {
+                None
+            } else {
+                let (waiter_tx, waiter_rx) = oneshot::channel();
+                self.proposal_waiters.lock().await.insert(proposal_id.clone(), waiter_tx);
+                Some(waiter_rx)
+            }
+        };
+
+        if let Some(waiter_rx) = waiter_rx {
+            if tokio::time::timeout(ACK_WAIT_TIMEOUT, waiter_rx).await.is_err() {
+                self.proposal_waiters.lock().await.remove(&proposal_id);
+                println!(
+                    "Node {} timed out waiting for acknowledgments on proposal: {}",
+                    self.id, proposal_id
+                );
+                return;
+            }
+        }
+
+        // Persist the commit before telling anyone about it, so a crash right
+        // after this point still recovers a node that believes it committed.
+        self.log
+            .append(&LogRecord::Commit {
+                proposal_id: proposal_id.clone(),
+                committed_state: State::Running, // This should match the state proposed earlier
+            })
+            .await
+            .expect("failed to persist commit to write-ahead log");
+
+        // Create and send a commit message, built per peer with its own
+        // negotiated minimum common version/capabilities.
+        for peer_id in self.peers.keys().copied().collect::<Vec<_>>() {
+            let (protocol_version, capabilities) = match self.negotiated_for(peer_id).await {
+                Ok((version, capabilities)) => (Some(version), Some(capabilities)),
+                Err(e) => {
+                    eprintln!("Failed to negotiate with peer {}: {:?}", peer_id, e);
+                    (None, None)
+                }
+            };
+            let commit_message = Message {
+                sender_id: self.id,
+                message_type: MessageType::Commit,
+                proposed_state: State::Running, // This should match the state proposed earlier
+                proposal_id: proposal_id.clone(),
+                protocol_version,
+                capabilities,
+            };
+
+            if let Err(e) = self.send_message(&commit_message, peer_id).await {
+                eprintln!("Failed to send commit: {:?}", e);
+            }
+        }
+
+        println!("Node {} committed the proposal: {}", self.id, proposal_id);
+    }
+
+    /// Handles incoming messages from a receiver, until the channel closes
+    /// or `shutdown` fires.
+    ///
+    /// # Arguments
+    ///
+    /// * `receiver` - The channel receiver used to receive messages.
+    pub async fn handle_incoming_messages(&self, mut receiver: mpsc::Receiver<Message>) {
+        let mut shutdown_rx = self.shutdown.subscribe();
+
+        loop {
+            let message = tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                _ = shutdown_rx.changed() => {
+                    println!("Node {} stopping message handler", self.id);
+                    break;
+                }
+            };
+
+            match message.message_type {
+                MessageType::Proposal => {
+                    // Handle proposal
+                    println!("Node {} received proposal: {:?}", self.id, message);
+                    self.log
+                        .append(&LogRecord::Proposal {
+                            proposal_id: message.proposal_id.clone(),
+                            proposed_state: message.proposed_state.clone(),
+                        })
+                        .await
+                        .expect("failed to persist proposal to write-ahead log");
+
+                    let (protocol_version, capabilities) = match self.negotiated_for(message.sender_id).await {
+                        Ok((version, capabilities)) => (Some(version), Some(capabilities)),
+                        Err(e) => {
+                            eprintln!("Failed to negotiate with peer {}: {:?}", message.sender_id, e);
+                            (None, None)
+                        }
+                    };
+                    let ack_message = Message {
+                        sender_id: self.id,
+                        message_type: MessageType::Acknowledgment,
+                        proposed_state: message.proposed_state.clone(),
+                        proposal_id: message.proposal_id.clone(),
+                        protocol_version,
+                        capabilities,
+                    };
+                    if let Err(e) = self.send_message(&ack_message, message.sender_id).await {
+                        eprintln!("Failed to send acknowledgment: {:?}", e);
+                    }
+
+                    // Update the state to the proposed state
+                    let mut state = self.state.lock().await;
+                    *state = message.proposed_state;
+                    println!("Node {} updated state to {:?}", self.id, *state);
+                }
+                MessageType::Acknowledgment => {
+                    // Handle acknowledgment
+                    println!("Node {} received acknowledgment: {:?}", self.id, message);
+                    self.log
+                        .append(&LogRecord::Acknowledgment {
+                            proposal_id: message.proposal_id.clone(),
+                            acknowledging_peer: message.sender_id,
+                        })
+                        .await
+                        .expect("failed to persist acknowledgment to write-ahead log");
+
+                    let ack_count = {
+                        let mut acks = self.proposal_acknowledgments.lock().await;
+                        let ack_set = acks.entry(message.proposal_id.clone()).or_insert_with(HashSet::new);
+                        ack_set.insert(message.sender_id);
+                        ack_set.len()
+                    };
+
+                    if ack_count >= self.majority() {
+                        if let Some(waiter) = self.proposal_waiters.lock().await.remove(&message.proposal_id) {
+                            let _ = waiter.send(());
+                        }
+                    }
+                }
+                MessageType::Commit => {
+                    // Persist the commit to our own write-ahead log, so a
+                    // restart's replay sees this proposal as resolved
+                    // instead of leaving it in `in_flight_proposals`
+                    // forever and resuming it over and over.
+                    println!("Node {} received commit: {:?}", self.id, message);
+                    self.log
+                        .append(&LogRecord::Commit {
+                            proposal_id: message.proposal_id.clone(),
+                            committed_state: message.proposed_state.clone(),
+                        })
+                        .await
+                        .expect("failed to persist commit to write-ahead log");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Listens for incoming connections, authenticates each one with the
+    /// handshake, and processes the encrypted messages it carries, until
+    /// `shutdown` fires.
+    ///
+    /// # Returns
+    ///
+    /// Returns an `io::Result<()>` indicating success or failure.
+    pub async fn listen(self: Arc<Self>) -> io::Result<()> {
+        let listener = TcpListener::bind(&self.address).await?;
+        println!("Node {} listening on {}", self.id, self.address);
+        let _ = self.listening.send(true);
+
+        let mut shutdown_rx = self.shutdown.subscribe();
+
+        loop {
+            let (mut socket, _) = tokio::select! {
+                result = listener.accept() => result?,
+                _ = shutdown_rx.changed() => {
+                    println!("Node {} shutting down listener", self.id);
+                    return Ok(());
+                }
+            };
+
+            let node = self.clone();
+            tokio::spawn(async move {
+                let (peer_id, public_key, session) = match handshake::perform(&mut socket, &node.identity, node.id, Role::Responder).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        println!("Rejecting connection: handshake failed: {:?}", e);
+                        return;
+                    }
+                };
+
+                match node.peers.get(&peer_id) {
+                    Some(peer) if peer.public_key == public_key => {}
+                    _ => {
+                        println!("Rejecting connection from unknown or unverified peer id {}", peer_id);
+                        return;
+                    }
+                }
+
+                match node.negotiate_capabilities(&mut socket, &session, Role::Responder).await {
+                    Ok((version, capabilities)) => {
+                        node.peer_capabilities.lock().await.insert(peer_id, (version, capabilities));
+                    }
+                    Err(e) => {
+                        println!("Rejecting connection: capability handshake failed: {:?}", e);
+                        return;
+                    }
+                }
+
+                let mut conn_shutdown_rx = node.shutdown.subscribe();
+
+                loop {
+                    let frame = tokio::select! {
+                        frame = read_frame(&mut socket) => frame,
+                        _ = conn_shutdown_rx.changed() => {
+                            println!("Node {} closing connection from peer {} for shutdown", node.id, peer_id);
+                            break;
+                        }
+                    };
+
+                    match frame {
+                        Ok(Some(ciphertext)) => {
+                            let payload = match session.decrypt(&ciphertext) {
+                                Ok(payload) => payload,
+                                Err(e) => {
+                                    println!("Dropping unauthenticated frame from peer {}: {:?}", peer_id, e);
+                                    continue;
+                                }
+                            };
+
+                            match serde_json::from_slice::<Message>(&payload) {
+                                Ok(message) if message.sender_id == peer_id => {
+                                    // `handle_incoming_messages` drops its receiver once
+                                    // `shutdown` fires, so a frame arriving in the brief
+                                    // window before the `select!` above observes the
+                                    // same signal should be dropped, not panic this task.
+                                    if node.tx.send(message).await.is_err() {
+                                        println!("Node {} message channel closed, dropping frame from peer {}", node.id, peer_id);
+                                        break;
+                                    }
+                                }
+                                Ok(message) => {
+                                    println!(
+                                        "Dropping message claiming sender {} over a session authenticated as {}",
+                                        message.sender_id, peer_id
+                                    );
+                                }
+                                Err(_) => println!("Failed to deserialize message"),
+                            }
+                        }
+                        Ok(None) => {
+                            println!("Connection closed");
+                            break;
+                        }
+                        Err(e) => {
+                            println!("Failed to read from socket: {:?}", e);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+/// Upper bound on an accepted frame's payload length, guarding against a corrupt
+/// or hostile length header causing an unbounded allocation.
+const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// Writes `payload` to `writer` prefixed with a 4-byte big-endian length header.
+///
+/// This is the wire format every `Message` is sent with: callers no longer hand
+/// a bare serialized blob to the socket, so a reader always knows exactly how
+/// many bytes make up the next message, regardless of how TCP happens to
+/// fragment or coalesce the underlying segments.
+pub async fn write_frame<W: AsyncWriteExt + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    let len = payload.len() as u32;
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+/// Reads a single length-prefixed frame from `reader`.
+///
+/// Returns `Ok(None)` if the connection was closed cleanly before a new frame
+/// began (i.e. exactly at a frame boundary), which callers should treat as the
+/// peer hanging up rather than an error.
+pub async fn read_frame<R: AsyncReadExt + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    let mut filled = 0;
+    while filled < len_buf.len() {
+        let n = reader.read(&mut len_buf[filled..]).await?;
+        if n == 0 {
+            if filled == 0 {
+                return Ok(None);
+            }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed inside a frame header"));
+        }
+        filled += n;
+    }
+
+    let len = u32::from_be_bytes(len_buf);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame length {} exceeds maximum {}", len, MAX_FRAME_LEN),
+        ));
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
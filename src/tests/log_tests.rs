@@ -0,0 +1,89 @@
+#[cfg(test)]
+mod tests {
+    use crate::node::log::LogRecord;
+    use crate::node::{state::State, WriteAheadLog};
+    use uuid::Uuid;
+
+    /// Returns a path to a fresh, uniquely-named temp file, so concurrently-running
+    /// tests don't trample each other's log.
+    fn temp_log_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("state_machine-test-{}.wal.log", Uuid::new_v4()))
+    }
+
+    /// Replaying a log that was never created should recover the default, freshly-initialized state.
+    #[tokio::test]
+    async fn test_replay_missing_log_returns_default() {
+        let path = temp_log_path();
+
+        let recovered = WriteAheadLog::replay(&path).await.unwrap();
+
+        assert_eq!(recovered.committed_state, State::Init);
+        assert!(recovered.in_flight_proposals.is_empty());
+    }
+
+    /// A proposal that reached a commit record should be reflected in
+    /// `committed_state` and not left in `in_flight_proposals`.
+    #[tokio::test]
+    async fn test_replay_recovers_committed_state() {
+        let path = temp_log_path();
+        let log = WriteAheadLog::open(&path).await.unwrap();
+
+        log.append(&LogRecord::Proposal {
+            proposal_id: "proposal-1".to_string(),
+            proposed_state: State::Running,
+        })
+        .await
+        .unwrap();
+        log.append(&LogRecord::Acknowledgment {
+            proposal_id: "proposal-1".to_string(),
+            acknowledging_peer: 2,
+        })
+        .await
+        .unwrap();
+        log.append(&LogRecord::Commit {
+            proposal_id: "proposal-1".to_string(),
+            committed_state: State::Running,
+        })
+        .await
+        .unwrap();
+
+        let recovered = WriteAheadLog::replay(&path).await.unwrap();
+
+        assert_eq!(recovered.committed_state, State::Running);
+        assert!(recovered.in_flight_proposals.is_empty());
+    }
+
+    /// A proposal recorded but never followed by a commit record should
+    /// survive replay as in-flight, with the state it proposed, so the node
+    /// can resume it.
+    #[tokio::test]
+    async fn test_replay_recovers_in_flight_proposal() {
+        let path = temp_log_path();
+        let log = WriteAheadLog::open(&path).await.unwrap();
+
+        log.append(&LogRecord::Proposal {
+            proposal_id: "proposal-1".to_string(),
+            proposed_state: State::Running,
+        })
+        .await
+        .unwrap();
+        log.append(&LogRecord::Commit {
+            proposal_id: "proposal-1".to_string(),
+            committed_state: State::Running,
+        })
+        .await
+        .unwrap();
+        log.append(&LogRecord::Proposal {
+            proposal_id: "proposal-2".to_string(),
+            proposed_state: State::Stopped,
+        })
+        .await
+        .unwrap();
+
+        let recovered = WriteAheadLog::replay(&path).await.unwrap();
+
+        assert_eq!(recovered.committed_state, State::Running);
+        assert_eq!(recovered.in_flight_proposals.get("proposal-2"), Some(&State::Stopped));
+        assert_eq!(recovered.in_flight_proposals.len(), 1);
+    }
+}
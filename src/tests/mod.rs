@@ -0,0 +1,2 @@
+mod log_tests;
+mod node_tests;